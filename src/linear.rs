@@ -6,6 +6,11 @@ use rgb::alt::*;
 pub trait GammaComponent {
     fn max_value() -> usize;
     fn to_linear(&self, lut: &[f64]) -> f64;
+
+    /// True for floating-point components, whose values can't index a LUT.
+    ///
+    /// `ToRGBAPLU`/`ToGLU` use this to skip LUT allocation entirely.
+    fn is_float() -> bool { false }
 }
 
 /// Downsampling should be done in linear RGB color space.
@@ -20,13 +25,79 @@ pub trait GammaPixel {
 
     fn to_linear(&self, gamma_lut: &[f64]) -> Self::Output;
 
+    /// Like `to_linear`, but scales each linear channel by its `ChannelWeights`
+    /// factor after gamma decoding.
+    ///
+    /// Color is premultiplied by the true alpha, while the alpha weight is
+    /// applied only to the stored `a`. When `weights.a != 1` this means the
+    /// usual `channel == straight_color * a` invariant no longer holds. This
+    /// inner method still returns the bare `Self::Output`; the public
+    /// `ToRGBAPLU::to_rgbaplu_weighted` wraps it in `WeightedRGBAPLU` so that
+    /// invariant can't be relied on by accident.
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> Self::Output;
+
+    /// Like `to_linear`, but keeps straight (non-premultiplied) color and
+    /// carries alpha separately instead of multiplying it in.
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> Self::Output;
+
     fn make_lut() -> Vec<f64> {
+        Self::make_lut_with(&TransferFunction::Srgb)
+    }
+
+    fn make_lut_with(tf: &TransferFunction) -> Vec<f64> {
+        if let TransferFunction::Custom(lut) = tf {
+            let expected = Self::Component::max_value() + 1;
+            assert_eq!(
+                lut.len(), expected,
+                "Custom transfer function LUT has {} entries, but this component type needs exactly {}",
+                lut.len(), expected,
+            );
+            return lut.clone();
+        }
         (0..Self::Component::max_value() + 1)
-            .map(|i| to_linear(i as f64 / Self::Component::max_value() as f64))
+            .map(|i| tf.to_linear(i as f64 / Self::Component::max_value() as f64))
             .collect()
     }
 }
 
+/// Transfer characteristics used to decode gamma-encoded values to linear light.
+///
+/// The default sRGB path assumed everywhere else bakes in the sRGB piecewise
+/// curve; this lets callers decode files tagged with a different gamma.
+#[derive(Clone, Debug)]
+pub enum TransferFunction {
+    /// Standard sRGB piecewise curve.
+    Srgb,
+    /// Pure power-law gamma, e.g. `Gamma(2.2)` or `Gamma(1.8)`.
+    Gamma(f64),
+    /// Rec.709 / BT.601 curve with its own breakpoint.
+    Rec709,
+    /// Content is already linear-light.
+    Linear,
+    /// Precomputed lookup table, used verbatim.
+    Custom(Vec<f64>),
+}
+
+impl TransferFunction {
+    fn to_linear(&self, s: f64) -> f64 {
+        match *self {
+            TransferFunction::Srgb => to_linear(s),
+            TransferFunction::Gamma(g) => s.powf(g),
+            TransferFunction::Rec709 => {
+                if s < 0.081 {
+                    s / 4.5
+                } else {
+                    ((s + 0.099) / 1.099).powf(1.0 / 0.45)
+                }
+            },
+            TransferFunction::Linear => s,
+            // Custom supplies its LUT directly and is short-circuited in `make_lut_with`,
+            // so this arm is never reached.
+            TransferFunction::Custom(_) => unreachable!("Custom supplies its LUT directly"),
+        }
+    }
+}
+
 fn to_linear(s: f64) -> f64 {
     if s <= 0.04045 {
         s / 12.92
@@ -40,13 +111,136 @@ fn to_linear(s: f64) -> f64 {
 /// Convenience function `.to_rgbaplu()` to convert RGBA bitmaps to a format useful for DSSIM.
 pub trait ToRGBAPLU {
     fn to_rgbaplu(&self) -> Vec<RGBAPLU>;
+
+    /// Like `to_rgbaplu()`, but decodes using the given transfer function
+    /// instead of assuming sRGB.
+    ///
+    /// Float components are already linear-light and are passed through
+    /// unchanged, so `tf` is ignored for `f32`/`f64` input (i.e. treated as
+    /// `TransferFunction::Linear`).
+    fn to_rgbaplu_with(&self, tf: &TransferFunction) -> Vec<RGBAPLU>;
+
+    /// Like `to_rgbaplu()`, but scales each linear channel by the given
+    /// per-channel weights so DSSIM can emphasize, e.g., green over blue.
+    ///
+    /// Returns `WeightedRGBAPLU` rather than `RGBAPLU`: with a non-default
+    /// `weights.a` the result is no longer a faithful premultiplied image
+    /// (see `GammaPixel::to_linear_weighted`), and the distinct type stops it
+    /// from being passed to `composite_over_white`/`ToLABA` by accident.
+    fn to_rgbaplu_weighted(&self, weights: ChannelWeights) -> Vec<WeightedRGBAPLU>;
+
+    /// Like `to_rgbaplu()`, but leaves color straight (non-premultiplied) with
+    /// alpha carried separately, so transparent-region color is preserved.
+    ///
+    /// Use `composite_over_white` to recover the "perceived over white" form.
+    fn to_rgbaplu_straight(&self) -> Vec<RGBAPLU>;
 }
 
+/// Composite a straight-alpha `RGBAPLU` (as produced by `to_rgbaplu_straight`)
+/// over an opaque white background, recovering a premultiplied-style pixel.
+pub fn composite_over_white(px: RGBAPLU) -> RGBAPLU {
+    RGBAPLU {
+        r: px.r * px.a + (1.0 - px.a),
+        g: px.g * px.a + (1.0 - px.a),
+        b: px.b * px.a + (1.0 - px.a),
+        a: 1.0,
+    }
+}
+
+/// Per-channel importance factors applied after gamma decoding.
+///
+/// Follows the R/G/B/A weighting used by the palette-quantization code, where
+/// green carries more perceptual weight than blue and alpha is reduced.
+#[derive(Copy, Clone, Debug)]
+pub struct ChannelWeights {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Default for ChannelWeights {
+    fn default() -> Self {
+        ChannelWeights { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }
+    }
+}
+
+/// Result of `ToRGBAPLU::to_rgbaplu_weighted`.
+///
+/// Same layout as `RGBAPLU`, but kept as a distinct type: with a non-default
+/// `weights.a` the usual `channel == straight_color * a` invariant doesn't
+/// hold, so this can't be passed to `composite_over_white`/`ToLABA` without
+/// unwrapping the field first, which forces a deliberate decision at the call
+/// site instead of a silently wrong result.
+#[derive(Copy, Clone, Debug)]
+pub struct WeightedRGBAPLU(pub RGBAPLU);
+
 /// Grayscale Linear-light Unit scale
 pub trait ToGLU {
     fn to_glu(&self) -> Vec<f64>;
 }
 
+/// CIE L\*a\*b\* computed from straight (un-premultiplied) color, with
+/// `alpha` carried separately.
+///
+/// `l` is in 0..100, `a`/`b` are roughly ±128. Perceptual comparison is more
+/// uniform in this opponent-color space than in linear RGB.
+#[derive(Copy, Clone, Debug)]
+pub struct LABA {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+    pub alpha: f64,
+}
+
+/// Convert gamma-encoded pixels to CIE L\*a\*b\*.
+///
+/// `.to_laba()` decodes to linear RGB (reusing the gamma LUT) and then maps to
+/// L\*a\*b\* via the D65 white point.
+pub trait ToLABA {
+    fn to_laba(&self) -> Vec<LABA>;
+}
+
+/// CIE nonlinearity used by the XYZ→L\*a\*b\* step.
+fn lab_f(t: f64) -> f64 {
+    if t > 0.008856 {
+        t.cbrt()
+    } else {
+        7.787 * t + 16.0 / 116.0
+    }
+}
+
+impl From<RGBAPLU> for LABA {
+    fn from(px: RGBAPLU) -> LABA {
+        // Un-premultiply first, so transparency doesn't get read as darkness.
+        let (r, g, b) = if px.a > 0.0 {
+            (px.r / px.a, px.g / px.a, px.b / px.a)
+        } else {
+            (0.0, 0.0, 0.0)
+        };
+        // Linear sRGB → XYZ (D65), normalized by the D65 white point.
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.0890;
+        let fx = lab_f(x);
+        let fy = lab_f(y);
+        let fz = lab_f(z);
+        LABA {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+            alpha: px.a,
+        }
+    }
+}
+
+impl<P> ToLABA for [P] where P: GammaPixel<Output=RGBAPLU> {
+    fn to_laba(&self) -> Vec<LABA> {
+        let gamma_lut = if P::Component::is_float() { Vec::new() } else { P::make_lut() };
+        self.iter().map(|px| LABA::from(px.to_linear(&gamma_lut))).collect()
+    }
+}
+
 impl GammaComponent for u8 {
     fn max_value() -> usize { 255 }
     fn to_linear(&self, lut: &[f64]) -> f64 {
@@ -61,9 +255,25 @@ impl GammaComponent for u16 {
     }
 }
 
+impl GammaComponent for f32 {
+    fn max_value() -> usize { 1 }
+    fn to_linear(&self, _lut: &[f64]) -> f64 {
+        *self as f64
+    }
+    fn is_float() -> bool { true }
+}
+
+impl GammaComponent for f64 {
+    fn max_value() -> usize { 1 }
+    fn to_linear(&self, _lut: &[f64]) -> f64 {
+        *self
+    }
+    fn is_float() -> bool { true }
+}
+
 impl<M> ToGLU for [M] where M: GammaPixel<Output=f64> {
     fn to_glu(&self) -> Vec<f64> {
-        let gamma_lut = M::make_lut();
+        let gamma_lut = if M::Component::is_float() { Vec::new() } else { M::make_lut() };
         self.iter().map(|px| px.to_linear(&gamma_lut)).collect()
     }
 }
@@ -80,6 +290,23 @@ impl<M> GammaPixel for RGBA<M> where M: Clone + Into<f64> + GammaComponent {
             a: a_unit,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        let a_unit = self.a.clone().into() / M::max_value() as f64;
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut) * weights.r * a_unit,
+            g: self.g.to_linear(gamma_lut) * weights.g * a_unit,
+            b: self.b.to_linear(gamma_lut) * weights.b * a_unit,
+            a: a_unit * weights.a,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut),
+            g: self.g.to_linear(gamma_lut),
+            b: self.b.to_linear(gamma_lut),
+            a: self.a.clone().into() / M::max_value() as f64,
+        }
+    }
 }
 
 impl<M> GammaPixel for BGRA<M> where M: Clone + Into<f64> + GammaComponent {
@@ -94,6 +321,23 @@ impl<M> GammaPixel for BGRA<M> where M: Clone + Into<f64> + GammaComponent {
             a: a_unit,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        let a_unit = self.a.clone().into() / M::max_value() as f64;
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut) * weights.r * a_unit,
+            g: self.g.to_linear(gamma_lut) * weights.g * a_unit,
+            b: self.b.to_linear(gamma_lut) * weights.b * a_unit,
+            a: a_unit * weights.a,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut),
+            g: self.g.to_linear(gamma_lut),
+            b: self.b.to_linear(gamma_lut),
+            a: self.a.clone().into() / M::max_value() as f64,
+        }
+    }
 }
 
 impl<M> GammaPixel for RGB<M> where M: GammaComponent {
@@ -107,6 +351,22 @@ impl<M> GammaPixel for RGB<M> where M: GammaComponent {
             a: 1.0,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut) * weights.r,
+            g: self.g.to_linear(gamma_lut) * weights.g,
+            b: self.b.to_linear(gamma_lut) * weights.b,
+            a: 1.0,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut),
+            g: self.g.to_linear(gamma_lut),
+            b: self.b.to_linear(gamma_lut),
+            a: 1.0,
+        }
+    }
 }
 
 impl<M> GammaPixel for BGR<M> where M: GammaComponent {
@@ -120,6 +380,22 @@ impl<M> GammaPixel for BGR<M> where M: GammaComponent {
             a: 1.0,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut) * weights.r,
+            g: self.g.to_linear(gamma_lut) * weights.g,
+            b: self.b.to_linear(gamma_lut) * weights.b,
+            a: 1.0,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        RGBAPLU {
+            r: self.r.to_linear(gamma_lut),
+            g: self.g.to_linear(gamma_lut),
+            b: self.b.to_linear(gamma_lut),
+            a: 1.0,
+        }
+    }
 }
 
 impl<M> GammaPixel for lodepng::GreyAlpha<M> where M: Copy + Clone + Into<f64> + GammaComponent {
@@ -135,6 +411,26 @@ impl<M> GammaPixel for lodepng::GreyAlpha<M> where M: Copy + Clone + Into<f64> +
             a: a_unit,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        let a_unit = self.1.clone().into() / M::max_value() as f64;
+        let g = self.0.to_linear(gamma_lut);
+        RGBAPLU {
+            r: g * weights.r * a_unit,
+            g: g * weights.g * a_unit,
+            b: g * weights.b * a_unit,
+            a: a_unit * weights.a,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        let a_unit = self.1.clone().into() / M::max_value() as f64;
+        let g = self.0.to_linear(gamma_lut);
+        RGBAPLU {
+            r: g,
+            g: g,
+            b: g,
+            a: a_unit,
+        }
+    }
 }
 
 impl<M> GammaPixel for M where M: GammaComponent {
@@ -143,6 +439,12 @@ impl<M> GammaPixel for M where M: GammaComponent {
     fn to_linear(&self, gamma_lut: &[f64]) -> f64 {
         self.to_linear(gamma_lut)
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> f64 {
+        GammaComponent::to_linear(self, gamma_lut) * weights.g
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> f64 {
+        GammaComponent::to_linear(self, gamma_lut)
+    }
 }
 
 impl<M> GammaPixel for lodepng::Grey<M> where M: Copy + GammaComponent {
@@ -157,11 +459,148 @@ impl<M> GammaPixel for lodepng::Grey<M> where M: Copy + GammaComponent {
             a: 1.0,
         }
     }
+    fn to_linear_weighted(&self, gamma_lut: &[f64], weights: &ChannelWeights) -> RGBAPLU {
+        let g = self.0.to_linear(gamma_lut);
+        RGBAPLU {
+            r: g * weights.r,
+            g: g * weights.g,
+            b: g * weights.b,
+            a: 1.0,
+        }
+    }
+    fn to_linear_straight(&self, gamma_lut: &[f64]) -> RGBAPLU {
+        let g = self.0.to_linear(gamma_lut);
+        RGBAPLU {
+            r: g,
+            g: g,
+            b: g,
+            a: 1.0,
+        }
+    }
 }
 
 impl<P> ToRGBAPLU for [P] where P: GammaPixel<Output=RGBAPLU> {
     fn to_rgbaplu(&self) -> Vec<RGBAPLU> {
-        let gamma_lut = P::make_lut();
+        let gamma_lut = if P::Component::is_float() { Vec::new() } else { P::make_lut() };
         self.iter().map(|px| px.to_linear(&gamma_lut)).collect()
     }
+
+    fn to_rgbaplu_with(&self, tf: &TransferFunction) -> Vec<RGBAPLU> {
+        let gamma_lut = if P::Component::is_float() { Vec::new() } else { P::make_lut_with(tf) };
+        self.iter().map(|px| px.to_linear(&gamma_lut)).collect()
+    }
+
+    fn to_rgbaplu_weighted(&self, weights: ChannelWeights) -> Vec<WeightedRGBAPLU> {
+        let gamma_lut = if P::Component::is_float() { Vec::new() } else { P::make_lut() };
+        self.iter().map(|px| WeightedRGBAPLU(px.to_linear_weighted(&gamma_lut, &weights))).collect()
+    }
+
+    fn to_rgbaplu_straight(&self) -> Vec<RGBAPLU> {
+        let gamma_lut = if P::Component::is_float() { Vec::new() } else { P::make_lut() };
+        self.iter().map(|px| px.to_linear_straight(&gamma_lut)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn srgb_endpoints() {
+        let px = [RGBA::new(0u8, 0, 0, 255), RGBA::new(255, 255, 255, 255)].to_rgbaplu();
+        assert!(close(px[0].r, 0.0));
+        assert!(close(px[1].r, 1.0));
+        assert!(close(px[1].g, 1.0));
+        assert!(close(px[1].b, 1.0));
+    }
+
+    #[test]
+    fn transfer_functions() {
+        let px = RGBA::new(188u8, 188, 188, 255);
+
+        // Linear passes the normalized value straight through.
+        let lin = [px].to_rgbaplu_with(&TransferFunction::Linear);
+        assert!(close(lin[0].r, 188.0 / 255.0));
+
+        // Pure power-law gamma.
+        let g = [px].to_rgbaplu_with(&TransferFunction::Gamma(2.2));
+        assert!(close(g[0].r, (188.0f64 / 255.0).powf(2.2)));
+
+        // Rec.709, one sample each side of the 0.081 breakpoint.
+        let low = RGBA::new(10u8, 10, 10, 255); // 10/255 ≈ 0.039 < 0.081
+        let high = RGBA::new(200u8, 200, 200, 255); // 200/255 ≈ 0.784 > 0.081
+        let rec = [low, high].to_rgbaplu_with(&TransferFunction::Rec709);
+        assert!(close(rec[0].r, (10.0 / 255.0) / 4.5));
+        assert!(close(rec[1].r, (((200.0 / 255.0) + 0.099) / 1.099).powf(1.0 / 0.45)));
+
+        // Custom LUT is used verbatim.
+        let lut: Vec<f64> = (0..=255).map(|i| i as f64 / 255.0).collect();
+        let custom = [px].to_rgbaplu_with(&TransferFunction::Custom(lut));
+        assert!(close(custom[0].r, 188.0 / 255.0));
+    }
+
+    #[test]
+    fn weighted_default_matches_unweighted() {
+        let pixels = [RGBA::new(10u8, 128, 250, 200), RGBA::new(0, 255, 64, 255)];
+        let plain = pixels.to_rgbaplu();
+        let weighted = pixels.to_rgbaplu_weighted(ChannelWeights::default());
+        for (p, w) in plain.iter().zip(weighted.iter()) {
+            assert!(close(p.r, w.0.r) && close(p.g, w.0.g) && close(p.b, w.0.b) && close(p.a, w.0.a));
+        }
+    }
+
+    #[test]
+    fn weighted_non_default_scales_each_channel() {
+        // Opaque, so alpha premultiply is 1.0 and only the weights scale color.
+        let pixels = [RGBA::new(200u8, 150, 100, 255)];
+        let plain = pixels.to_rgbaplu();
+        let weights = ChannelWeights { r: 0.5, g: 2.0, b: 0.25, a: 0.5 };
+        let weighted = pixels.to_rgbaplu_weighted(weights);
+        assert!(close(weighted[0].0.r, plain[0].r * 0.5));
+        assert!(close(weighted[0].0.g, plain[0].g * 2.0));
+        assert!(close(weighted[0].0.b, plain[0].b * 0.25));
+        assert!(close(weighted[0].0.a, 0.5));
+    }
+
+    #[test]
+    fn white_is_l100() {
+        let laba = [RGBA::new(255u8, 255, 255, 255)].to_laba();
+        assert!((laba[0].l - 100.0).abs() < 0.01);
+        assert!(laba[0].a.abs() < 0.01);
+        assert!(laba[0].b.abs() < 0.01);
+    }
+
+    #[test]
+    fn composite_over_white_round_trip() {
+        // Opaque color is returned unchanged.
+        let opaque = composite_over_white(RGBAPLU { r: 0.5, g: 0.25, b: 0.75, a: 1.0 });
+        assert!(close(opaque.r, 0.5) && close(opaque.g, 0.25) && close(opaque.b, 0.75) && close(opaque.a, 1.0));
+        // Fully transparent collapses to white.
+        let transparent = composite_over_white(RGBAPLU { r: 0.5, g: 0.25, b: 0.75, a: 0.0 });
+        assert!(close(transparent.r, 1.0) && close(transparent.g, 1.0) && close(transparent.b, 1.0));
+    }
+
+    #[test]
+    fn straight_keeps_unpremultiplied_color() {
+        let px = RGBA::new(255u8, 0, 0, 128);
+        let straight = [px].to_rgbaplu_straight();
+        let premult = [px].to_rgbaplu();
+        // Straight alpha keeps full-intensity red with alpha carried separately.
+        assert!(close(straight[0].r, 1.0));
+        assert!(close(straight[0].a, 128.0 / 255.0));
+        // Premultiplied darkens the color by alpha, so the two differ.
+        assert!(close(premult[0].r, 1.0 * (128.0 / 255.0)));
+        assert!(straight[0].r > premult[0].r);
+    }
+
+    #[test]
+    fn float_path_is_linear_passthrough() {
+        // f32 components are already linear-light; no LUT is built for them.
+        let px = [RGBA::new(0.5f32, 0.25, 0.75, 1.0)].to_rgbaplu();
+        assert!(close(px[0].r, 0.5) && close(px[0].g, 0.25) && close(px[0].b, 0.75));
+    }
 }